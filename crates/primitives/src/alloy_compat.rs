@@ -22,21 +22,10 @@ impl TryFrom<alloy_rpc_types::Block> for Block {
                     .into_iter()
                     .map(|tx| {
                         let signature = tx.signature.ok_or(ConversionError::MissingSignature)?;
-                        let recovery_id = if signature.v > U256::from(1) {
-                            signature.v - U256::from(37)
-                        } else {
-                            signature.v
-                        };
+                        let odd_y_parity = derive_odd_y_parity(&signature, tx.chain_id)?;
                         Ok(TransactionSigned::from_transaction_and_signature(
                             tx.try_into()?,
-                            crate::Signature {
-                                r: signature.r,
-                                s: signature.s,
-                                odd_y_parity: signature
-                                    .y_parity
-                                    .unwrap_or(Parity(recovery_id == U256::from(1)))
-                                    .0,
-                            },
+                            crate::Signature { r: signature.r, s: signature.s, odd_y_parity },
                         ))
                     })
                     .collect(),
@@ -57,6 +46,32 @@ impl TryFrom<alloy_rpc_types::Block> for Block {
     }
 }
 
+/// Derive the `odd_y_parity` bit of a transaction signature.
+///
+/// Typed transactions carry it explicitly as `y_parity`. Legacy transactions
+/// encode it in `v`: EIP-155 transactions as `v = 35 + 2 * chain_id + parity`,
+/// and pre-EIP-155 transactions with the `27/28` convention.
+fn derive_odd_y_parity(
+    signature: &alloy_rpc_types::Signature,
+    chain_id: Option<u64>,
+) -> Result<bool, alloy_rpc_types::ConversionError> {
+    use alloy_rpc_types::ConversionError;
+
+    if let Some(Parity(parity)) = signature.y_parity {
+        return Ok(parity);
+    }
+    let v = signature.v;
+    Ok(if v <= U256::from(1) {
+        v == U256::from(1)
+    } else if v == U256::from(27) || v == U256::from(28) {
+        v == U256::from(28)
+    } else {
+        let chain_id = chain_id.ok_or(ConversionError::MissingChainId)?;
+        let base = U256::from(35) + U256::from(2) * U256::from(chain_id);
+        v - base == U256::from(1)
+    })
+}
+
 impl TryFrom<alloy_rpc_types::Header> for Header {
     type Error = alloy_rpc_types::ConversionError;
 
@@ -201,7 +216,80 @@ impl TryFrom<alloy_rpc_types::Transaction> for Transaction {
                 }))
             }
             #[cfg(feature = "optimism")]
-            Some(TxType::Deposit) => todo!(),
+            Some(TxType::Deposit) => {
+                use alloy_primitives::B256;
+
+                /// The OP-stack-specific fields of a deposit transaction, parsed
+                /// from the non-standard members of the JSON-RPC response.
+                #[derive(serde::Deserialize)]
+                #[serde(rename_all = "camelCase")]
+                struct DepositFields {
+                    source_hash: B256,
+                    #[serde(default)]
+                    mint: U256,
+                    #[serde(default)]
+                    is_system_transaction: bool,
+                }
+
+                // The OP-stack deposit (system) fields are carried outside the
+                // standard JSON-RPC transaction object, in `other`.
+                let fields = tx
+                    .other
+                    .deserialize_into::<DepositFields>()
+                    .map_err(|e| ConversionError::Eip2718Error(RlpError::Custom(e).into()))?;
+                Ok(Transaction::Deposit(crate::TxDeposit {
+                    source_hash: fields.source_hash,
+                    from: tx.from,
+                    to: tx.to.map_or(TxKind::Create, TxKind::Call),
+                    mint: (fields.mint != U256::ZERO)
+                        .then(|| fields.mint.try_into())
+                        .transpose()
+                        .map_err(|_| ConversionError::Eip2718Error(RlpError::Overflow.into()))?,
+                    value: tx.value,
+                    gas_limit: tx
+                        .gas
+                        .try_into()
+                        .map_err(|_| ConversionError::Eip2718Error(RlpError::Overflow.into()))?,
+                    is_system_transaction: fields.is_system_transaction,
+                    input: tx.input,
+                }))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloy_rpc_types::Signature;
+
+    fn signature(v: u64, y_parity: Option<bool>) -> Signature {
+        Signature {
+            r: U256::from(1),
+            s: U256::from(2),
+            v: U256::from(v),
+            y_parity: y_parity.map(Parity),
         }
     }
+
+    #[test]
+    fn legacy_pre_eip155_parity() {
+        // No chain id, `v` follows the 27/28 convention.
+        assert!(!derive_odd_y_parity(&signature(27, None), None).unwrap());
+        assert!(derive_odd_y_parity(&signature(28, None), None).unwrap());
+    }
+
+    #[test]
+    fn eip155_parity_on_non_mainnet_chain() {
+        // Optimism mainnet chain id 10: v = 35 + 2 * 10 + parity = 55 or 56.
+        assert!(!derive_odd_y_parity(&signature(55, None), Some(10)).unwrap());
+        assert!(derive_odd_y_parity(&signature(56, None), Some(10)).unwrap());
+        // The old hardcoded `v - 37` would have read both of these as parity 0.
+    }
+
+    #[test]
+    fn typed_tx_prefers_explicit_y_parity() {
+        assert!(derive_odd_y_parity(&signature(0, Some(true)), Some(1)).unwrap());
+        assert!(!derive_odd_y_parity(&signature(1, Some(false)), Some(1)).unwrap());
+    }
 }
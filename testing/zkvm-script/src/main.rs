@@ -1,39 +1,59 @@
 //! A simple script that has takes in a block & RPC, fetches the block.
-pub mod cache;
+pub mod mpt;
 pub mod provider_db;
 pub mod witness;
 use async_std::task;
 
-use crate::{cache::CachedProvider, provider_db::RpcDb, witness::WitnessDb};
+use crate::{provider_db::RpcDb, witness::WitnessDb};
 
+use alloy_rlp::RlpEncodable;
 use eyre::Ok;
 use reth_evm::execute::{BlockExecutionOutput, BlockExecutorProvider, Executor};
 use reth_interfaces::executor::BlockValidationError;
 use reth_primitives::{
-    trie::AccountProof, Address, Block as RethBlock, ChainSpecBuilder, Receipts, B256, MAINNET,
+    trie::{AccountProof, StorageProof},
+    Address, Block as RethBlock, ChainSpec, ChainSpecBuilder, Header, B256, KECCAK_EMPTY, MAINNET,
 };
-use reth_provider::BundleStateWithReceipts;
-use revm::db::CacheDB;
-use revm_primitives::{keccak256, Bytecode, HashMap, U256};
+use std::sync::Arc;
+use revm::db::{states::BundleState, AccountState, CacheDB};
+use revm_primitives::{keccak256, AccountInfo, Bytecode, HashMap, U256};
 use url::Url;
 
+use crate::mpt::{self, MptNode, EMPTY_ROOT};
+
 #[derive(Debug, Clone)]
-/// A struct that holds the input for a zkVM program to execute a block.
+/// A struct that holds the input for a zkVM program to execute a range of
+/// consecutive blocks.
 pub struct SP1Input {
-    /// The previous block.
+    /// The parent of the first block in [`Self::blocks`], used to root the
+    /// pre-state trie and anchor the `parent_hash` chain.
     pub prev_block: RethBlock,
-    /// The block that will be executed inside the zkVM program.
-    pub block: RethBlock,
-    /// Address to merkle proofs.
+    /// The ordered, consecutive blocks to execute inside the zkVM program.
+    pub blocks: Vec<RethBlock>,
+    /// The merged, de-duplicated witness covering every account touched across
+    /// the whole range.
     pub address_to_proof: HashMap<Address, FullAccountProof>,
-    /// Block number to block hash.
+    /// Block number to block hash, for every ancestor a `BLOCKHASH` opcode read
+    /// during the range.
     pub block_hashes: HashMap<U256, B256>,
+    /// The contiguous ancestor headers, from the lowest block `BLOCKHASH` reached
+    /// up to the range's parent, used to anchor and verify [`Self::block_hashes`].
+    pub ancestor_headers: Vec<Header>,
+    /// Extra trie nodes the account proofs do not carry: the surviving siblings of
+    /// branches that collapse when a leaf is deleted. `eth_getProof` returns only
+    /// the path to each key, not the sibling subtrees, so a deletion that collapses
+    /// a branch onto an out-of-proof leaf or extension needs that sibling supplied
+    /// here for the re-hash to reproduce the canonical node.
+    pub extra_nodes: Vec<Vec<u8>>,
 }
 
 #[derive(Debug, Clone)]
 pub struct FullAccountProof {
     account_proof: AccountProof,
     code: Bytecode,
+    /// EIP-1186 storage proofs for exactly the slots touched during execution,
+    /// keyed by the (unhashed) storage slot.
+    storage_proofs: HashMap<B256, StorageProof>,
 }
 
 impl FullAccountProof {
@@ -45,29 +65,173 @@ impl FullAccountProof {
         if self.account_proof.info.unwrap().bytecode_hash.unwrap() != code_hash {
             return Err(eyre::eyre!("Code hash does not match the code"));
         }
+        // Every witnessed storage slot must prove against the account's storage
+        // root, so an SLOAD inside the zkVM can only read verified state.
+        for (slot, proof) in &self.storage_proofs {
+            proof.verify(self.account_proof.storage_root).map_err(|err| {
+                eyre::eyre!("storage proof for slot {slot} failed: {err}")
+            })?;
+        }
         Ok(())
     }
 }
 
-async fn get_input(block_number: u64, rpc_url: Url) -> eyre::Result<SP1Input> {
+/// A flat, de-duplicated witness: every account and storage trie node across
+/// the whole range, keyed by its keccak hash.
+///
+/// The per-account [`FullAccountProof`] form repeats the branches near the root
+/// of the trie in every account's proof; collapsing to a hash-keyed node set
+/// stores each shared node once. [`SP1Input`] still carries the per-account
+/// form and this set is derived from it by [`SP1Input::trie_nodes`]; the
+/// state-root recomputation reconstructs tries by following hash references
+/// into this set, while [`WitnessDb`] reads from the per-account proofs.
+#[derive(Debug, Clone, Default)]
+pub struct TrieNodes(pub HashMap<B256, Vec<u8>>);
+
+impl TrieNodes {
+    /// Insert a node, keyed by its keccak hash, returning that hash.
+    pub fn insert_node(&mut self, rlp: Vec<u8>) -> B256 {
+        let hash = keccak256(&rlp);
+        self.0.insert(hash, rlp);
+        hash
+    }
+}
+
+impl SP1Input {
+    /// Collapse the per-account `address_to_proof` proofs into a single
+    /// de-duplicated node set — the compact witness representation.
+    pub fn trie_nodes(&self) -> TrieNodes {
+        let mut nodes = TrieNodes::default();
+        for full in self.address_to_proof.values() {
+            for node in &full.account_proof.proof {
+                nodes.insert_node(node.to_vec());
+            }
+            for proof in full.storage_proofs.values() {
+                for node in &proof.proof {
+                    nodes.insert_node(node.to_vec());
+                }
+            }
+        }
+        // Siblings that `eth_getProof` omits but branch collapses need.
+        for rlp in &self.extra_nodes {
+            nodes.insert_node(rlp.clone());
+        }
+        nodes
+    }
+}
+
+/// The block executor for L1 mainnet blocks.
+#[cfg(not(feature = "optimism"))]
+fn executor_provider(chain_spec: Arc<ChainSpec>) -> impl BlockExecutorProvider {
+    reth_node_ethereum::EthExecutorProvider::ethereum(chain_spec)
+}
+
+/// The block executor for OP-stack blocks, so the STF verifier can run against
+/// chains whose blocks carry deposit (system) transactions.
+#[cfg(feature = "optimism")]
+fn executor_provider(chain_spec: Arc<ChainSpec>) -> impl BlockExecutorProvider {
+    reth_node_optimism::OpExecutorProvider::optimism(chain_spec)
+}
+
+/// The RLP layout of an account leaf in the state trie.
+#[derive(RlpEncodable)]
+struct TrieAccount {
+    nonce: u64,
+    balance: U256,
+    storage_root: B256,
+    code_hash: B256,
+}
+
+/// Recompute the post-state root of `block` entirely from the witness.
+///
+/// The account proofs in `sp1_input` give us every trie node along the path to
+/// each touched account; [`mpt::from_map`] stitches them into a partial trie
+/// rooted at the pre-state root. We then splice the post-execution leaves from
+/// `bundle` into that trie and re-hash bottom-up. For self-destructed or emptied
+/// accounts the leaf is removed and any now-single-child branch collapses into
+/// a leaf or extension.
+fn compute_state_root(sp1_input: &SP1Input, bundle: &BundleState) -> eyre::Result<B256> {
+    // Walk the single canonical node set rather than per-account proof lists.
+    let nodes = sp1_input.trie_nodes();
+    let pre_state_root = sp1_input.prev_block.header.state_root;
+    let mut trie = mpt::from_map(pre_state_root, &nodes.0);
+
+    for (address, account) in &bundle.state {
+        let hashed = mpt::nibbles(keccak256(address).as_slice());
+        let Some(info) = account.info.as_ref() else {
+            // Account was self-destructed or emptied: drop its leaf.
+            trie.remove(&hashed)?;
+            continue;
+        };
+
+        let storage_root = compute_storage_root(sp1_input, &nodes, address, account)?;
+        let leaf = TrieAccount {
+            nonce: info.nonce,
+            balance: info.balance,
+            storage_root,
+            code_hash: if info.code_hash == B256::ZERO { KECCAK_EMPTY } else { info.code_hash },
+        };
+        trie.insert(&hashed, alloy_rlp::encode(&leaf))?;
+    }
+
+    Ok(trie.hash())
+}
+
+/// Recompute a single account's storage root from the per-slot storage proofs
+/// in the witness, applying the slots that changed during execution.
+fn compute_storage_root(
+    sp1_input: &SP1Input,
+    nodes: &TrieNodes,
+    address: &Address,
+    account: &revm::db::states::BundleAccount,
+) -> eyre::Result<B256> {
+    let proof = sp1_input
+        .address_to_proof
+        .get(address)
+        .ok_or_else(|| eyre::eyre!("missing account proof for {address}"))?;
+
+    // Untouched storage keeps the root witnessed in the account proof.
+    if account.storage.is_empty() {
+        return Ok(proof.account_proof.storage_root);
+    }
+
+    // The storage trie is reconstructed from the same canonical node set.
+    let mut trie = mpt::from_map(proof.account_proof.storage_root, &nodes.0);
+
+    for (slot, value) in &account.storage {
+        let hashed = mpt::nibbles(keccak256(B256::from(*slot)).as_slice());
+        let present = value.present_value;
+        if present.is_zero() {
+            trie.remove(&hashed)?;
+        } else {
+            // Storage values are RLP-encoded as big-endian byte strings.
+            trie.insert(&hashed, alloy_rlp::encode(present))?;
+        }
+    }
+
+    let root = trie.hash();
+    Ok(if root == EMPTY_ROOT { EMPTY_ROOT } else { root })
+}
+
+/// Fetch and build the witness for the inclusive block range `start..=end`.
+///
+/// The trusted execution pass runs over every block in the range, threading the
+/// cumulative [`BundleState`] so each block sees its predecessors' writes. This
+/// is what lets `RpcDb` discover the full touched-account/slot set, from which
+/// it emits a single merged, de-duplicated witness for the whole range.
+async fn get_input(start: u64, end: u64, rpc_url: Url) -> eyre::Result<SP1Input> {
     // We put imports here that are not used in the zkVM program.
     use alloy_provider::{Provider as AlloyProvider, ReqwestProvider};
 
+    if end < start {
+        return Err(eyre::eyre!("empty block range {start}..={end}"));
+    }
+
     // Initialize a provider.
     let provider = ReqwestProvider::new_http(rpc_url);
     let merkle_block_td = U256::ZERO;
     // provider.header_td_by_number(block_number)?.unwrap_or_default();
 
-    let alloy_block = provider
-        .get_block_by_number(block_number.into(), true)
-        .await?
-        .ok_or(eyre::eyre!("block not found"))?;
-
-    let block = RethBlock::try_from(alloy_block)?;
-    for transaction in &block.body {
-        println!("Transaction: {:?}", transaction);
-    }
-
     let chain_spec = ChainSpecBuilder::default()
         .chain(MAINNET.chain)
         .genesis(
@@ -78,84 +242,186 @@ async fn get_input(block_number: u64, rpc_url: Url) -> eyre::Result<SP1Input> {
         )
         .shanghai_activated()
         .build();
-    // let cache_provider = CachedProvider::new(provider, "cache.json".into());
 
     let prev_alloy_block = provider
-        .get_block_by_number((block_number - 1).into(), true)
+        .get_block_by_number((start - 1).into(), true)
         .await?
         .ok_or(eyre::eyre!("prev_block not found"))?;
     let prev_block = RethBlock::try_from(prev_alloy_block)?;
     let prev_state_root = prev_block.header.state_root;
 
-    let cache_provider = provider.clone();
     let provider_db =
-        RpcDb::new(cache_provider.clone(), (block_number - 1).into(), prev_state_root.into());
+        RpcDb::new(provider.clone(), (start - 1).into(), prev_state_root.into());
     // The reason we can clone the provider_db is all the stateful elements are within Arcs.
-    let db = CacheDB::new(provider_db.clone());
 
     let address: Address = "0x4e68ccd3e89f51c3074ca5072bbac773960dfa36".parse().unwrap();
     let account = task::block_on(provider_db.fetch_account_info(address));
     // let account = provider_db.fetch_account_info(address).await;
     println!("Account: {:?}", account);
-    // cache_provider.save();
-
-    println!("Executing block with provider db...");
-    let executor =
-        reth_node_ethereum::EthExecutorProvider::ethereum(chain_spec.clone().into()).executor(db);
-    let BlockExecutionOutput { state, receipts, .. } = executor.execute(
-        (
-            &block
-                .clone()
-                .with_recovered_senders()
-                .ok_or(BlockValidationError::SenderRecoveryError)?,
-            (merkle_block_td + block.header.difficulty).into(),
-        )
-            .into(),
-    )?;
-    let _block_state = BundleStateWithReceipts::new(
-        state,
-        Receipts::from_block_receipt(receipts),
-        block.header.number,
-    );
-    println!("Done processing block!");
-    // cache_provider.save();
-
-    // let _next_block = provider
-    //     .get_block_by_number((block_number + 1).into(), false)
-    //     .await?
-    //     .ok_or(eyre::eyre!("next_block not found"))?;
-
-    // TODO: how do we compute the new state root here? Is there a way to do this incrementally?
-    // // Unpacked `BundleState::state_root_slow` function
-    // let (in_memory_state_root, in_memory_updates) =
-    //     block_state.hash_state_slow().state_root_with_updates(provider.tx_ref())?;
-    // TODO: check that the computed state_root matches the next_block.header.state_root
-
-    let sp1_input = provider_db.get_sp1_input(&prev_block, &block).await;
+
+    // Fetch every block in the range and replay it against the provider-backed
+    // DB, threading the cumulative state forward so the touched set is complete.
+    let mut blocks = Vec::with_capacity((end - start + 1) as usize);
+    let mut cumulative = BundleState::default();
+    for block_number in start..=end {
+        let alloy_block = provider
+            .get_block_by_number(block_number.into(), true)
+            .await?
+            .ok_or(eyre::eyre!("block {block_number} not found"))?;
+        let block = RethBlock::try_from(alloy_block)?;
+
+        println!("Executing block {block_number} with provider db...");
+        let mut db = CacheDB::new(provider_db.clone());
+        seed_db(&mut db, &cumulative);
+        let executor = executor_provider(chain_spec.clone().into())
+            .executor(db);
+        let BlockExecutionOutput { state, .. } = executor.execute(
+            (
+                &block
+                    .clone()
+                    .with_recovered_senders()
+                    .ok_or(BlockValidationError::SenderRecoveryError)?,
+                (merkle_block_td + block.header.difficulty).into(),
+            )
+                .into(),
+        )?;
+        cumulative.extend(state);
+        blocks.push(block);
+    }
+    println!("Done processing {} blocks!", blocks.len());
+
+    // Emit one merged witness covering the union of everything touched.
+    let sp1_input = provider_db.get_sp1_input(&prev_block, &blocks).await;
 
     println!("Instantiating WitnessDb from SP1Input...");
     // This code will be the code that runs inside the zkVM.
     let witness_db_inner = WitnessDb::new(sp1_input.clone());
-    let witness_db = CacheDB::new(witness_db_inner);
-    println!("Executing block with witness db...");
-    let executor = reth_node_ethereum::EthExecutorProvider::ethereum(chain_spec.clone().into())
-        .executor(witness_db);
-    let BlockExecutionOutput { state, receipts, .. } = executor.execute(
-        (
-            &block
-                .clone()
-                .with_recovered_senders()
-                .ok_or(BlockValidationError::SenderRecoveryError)?,
-            (merkle_block_td + block.header.difficulty).into(),
-        )
-            .into(),
-    )?;
-    println!("Done processing block!");
+    let mut witness_cumulative = BundleState::default();
+    for block in &sp1_input.blocks {
+        let mut db = CacheDB::new(witness_db_inner.clone());
+        seed_db(&mut db, &witness_cumulative);
+        println!("Executing block {} with witness db...", block.header.number);
+        let executor = executor_provider(chain_spec.clone().into())
+            .executor(db);
+        let BlockExecutionOutput { state, .. } = executor.execute(
+            (
+                &block
+                    .clone()
+                    .with_recovered_senders()
+                    .ok_or(BlockValidationError::SenderRecoveryError)?,
+                (merkle_block_td + block.header.difficulty).into(),
+            )
+                .into(),
+        )?;
+        witness_cumulative.extend(state);
+    }
+    println!("Done processing blocks with witness db!");
 
     Ok(sp1_input.clone())
 }
 
-/// Program that verifies the STF, run inside the zkVM.
+/// Verify the witnessed `BLOCKHASH` inputs so a `BLOCKHASH` opcode cannot be fed
+/// arbitrary values inside the zkVM.
+///
+/// Every hash the zkVM will serve is checked against a trusted header. Trust is
+/// rooted at the range's parent (whose hash the pre-state is committed to) and
+/// extended by verifying the contiguous `parent_hash` links through the
+/// witnessed ancestor headers (downwards) and the range's own blocks (upwards):
+/// `block_hashes[n]` must equal the `parent_hash` of the header at `n + 1`. Post
+/// EIP-2935-activation chains could alternatively source these from the history
+/// storage contract, but the header chain is sufficient and chain-agnostic.
+fn verify_block_hashes(sp1_input: &SP1Input) -> eyre::Result<()> {
+    if sp1_input.block_hashes.is_empty() {
+        return Ok(());
+    }
+
+    // Index every header we hold by its number: the range's parent (the trusted
+    // anchor), the witnessed ancestor headers, and the range's own blocks.
+    let prev = &sp1_input.prev_block.header;
+    let mut by_number: HashMap<u64, &Header> = HashMap::new();
+    by_number.insert(prev.number, prev);
+    for header in &sp1_input.ancestor_headers {
+        by_number.insert(header.number, header);
+    }
+    for block in &sp1_input.blocks {
+        by_number.insert(block.header.number, &block.header);
+    }
+
+    // Starting from the trusted anchor, verify the contiguous parent_hash links
+    // so each held header's hash becomes trusted. Walk downwards over ancestors
+    // then upwards over the range blocks.
+    let mut verified: HashMap<u64, B256> = HashMap::new();
+    verified.insert(prev.number, prev.hash_slow());
+
+    let mut number = prev.number;
+    while number > 0 {
+        let header = by_number[&number];
+        let parent_number = number - 1;
+        let Some(parent) = by_number.get(&parent_number) else { break };
+        if parent.hash_slow() != header.parent_hash {
+            return Err(eyre::eyre!("ancestor header {parent_number} does not chain to {number}"));
+        }
+        verified.insert(parent_number, header.parent_hash);
+        number = parent_number;
+    }
+
+    let mut number = prev.number;
+    while let Some(child) = by_number.get(&(number + 1)) {
+        if child.parent_hash != verified[&number] {
+            return Err(eyre::eyre!("block {} does not chain to {number}", number + 1));
+        }
+        verified.insert(number + 1, child.hash_slow());
+        number += 1;
+    }
+
+    // Every hash the zkVM will serve for BLOCKHASH must match a trusted header.
+    for (num, hash) in &sp1_input.block_hashes {
+        let n = num.to::<u64>();
+        let trusted = verified
+            .get(&n)
+            .ok_or_else(|| eyre::eyre!("no witnessed header to verify block hash for block {n}"))?;
+        if trusted != hash {
+            return Err(eyre::eyre!("block hash mismatch for block {n}"));
+        }
+    }
+
+    Ok(())
+}
+
+/// Seed `db` with the accounts and storage accumulated so far so the next block
+/// in the range executes against the state left behind by its predecessors.
+fn seed_db<DB: revm::DatabaseRef>(db: &mut CacheDB<DB>, bundle: &BundleState)
+where
+    DB::Error: std::fmt::Debug,
+{
+    for (address, account) in &bundle.state {
+        match account.info.clone() {
+            Some(info) => {
+                db.insert_account_info(*address, info);
+                for (slot, value) in &account.storage {
+                    let _ = db.insert_account_storage(*address, *slot, value.present_value);
+                }
+            }
+            // The account was self-destructed/emptied by an earlier block in the
+            // range. Seed it as explicitly non-existent with no storage so a later
+            // block reads empty state rather than falling through to its stale
+            // pre-range value in the underlying db.
+            None => {
+                let entry = db.accounts.entry(*address).or_default();
+                entry.info = AccountInfo::default();
+                entry.storage.clear();
+                entry.account_state = AccountState::NotExisting;
+            }
+        }
+    }
+}
+
+/// Program that verifies the STF over a range of blocks, run inside the zkVM.
+///
+/// Blocks are executed in order, threading the cumulative [`BundleState`] from
+/// block N into the starting state for block N+1. Each block must link to its
+/// predecessor via `parent_hash`, and each block's recomputed post-state root
+/// must match the `state_root` in its own header.
 fn verify_stf(sp1_input: SP1Input) -> eyre::Result<()> {
     let chain_spec = ChainSpecBuilder::default()
         .chain(MAINNET.chain)
@@ -167,49 +433,71 @@ fn verify_stf(sp1_input: SP1Input) -> eyre::Result<()> {
         )
         .shanghai_activated()
         .build();
-    let block = sp1_input.block.clone();
     let merkle_block_td = U256::from(0); // TODO: this should be an input?
 
+    // Constrain the BLOCKHASH inputs before any block is executed.
+    verify_block_hashes(&sp1_input)?;
+
     let witness_db_inner = WitnessDb::new(sp1_input.clone());
-    let witness_db = CacheDB::new(witness_db_inner);
-
-    // let provider_db = RpcDb::new(provider.clone(), (block_number - 1).into());
-    // let db = CacheDB::new(provider_db.clone());
-    // let check_db =
-    //     witness_db::CheckDb { witness: witness_db.clone(), rpc: RpcDb::new(provider_db) };
-
-    // TODO: can we import `EthExecutorProvider` from reth-evm instead of reth-node-ethereum?
-    let executor = reth_node_ethereum::EthExecutorProvider::ethereum(chain_spec.clone().into())
-        .executor(witness_db);
-    let BlockExecutionOutput { state, receipts, .. } = executor.execute(
-        (
-            &block
-                .clone()
-                .with_recovered_senders()
-                .ok_or(BlockValidationError::SenderRecoveryError)?,
-            (merkle_block_td + block.header.difficulty).into(),
-        )
-            .into(),
-    )?;
-    let block_state = BundleStateWithReceipts::new(
-        state,
-        Receipts::from_block_receipt(receipts),
-        block.header.number,
-    );
-
-    // TODO: either return or verify the resulting state root.
+
+    let mut cumulative = BundleState::default();
+    let mut parent_header = sp1_input.prev_block.header.clone();
+
+    for block in &sp1_input.blocks {
+        // Each block must chain onto the previously verified header.
+        if block.header.parent_hash != parent_header.hash_slow() {
+            return Err(eyre::eyre!(
+                "block {} parent_hash does not link to block {}",
+                block.header.number,
+                parent_header.number
+            ));
+        }
+
+        // Replay the state accumulated by earlier blocks on top of the witness.
+        let mut db = CacheDB::new(witness_db_inner.clone());
+        seed_db(&mut db, &cumulative);
+
+        let executor = executor_provider(chain_spec.clone().into())
+            .executor(db);
+        let BlockExecutionOutput { state, .. } = executor.execute(
+            (
+                &block
+                    .clone()
+                    .with_recovered_senders()
+                    .ok_or(BlockValidationError::SenderRecoveryError)?,
+                (merkle_block_td + block.header.difficulty).into(),
+            )
+                .into(),
+        )?;
+        cumulative.extend(state);
+
+        // Recompute the post-state root from the witness trie and assert it
+        // matches the claimed root in this block's header.
+        let computed_root = compute_state_root(&sp1_input, &cumulative)?;
+        if computed_root != block.header.state_root {
+            return Err(eyre::eyre!(
+                "state root mismatch at block {}: computed {computed_root}, expected {}",
+                block.header.number,
+                block.header.state_root
+            ));
+        }
+
+        parent_header = block.header.clone();
+    }
+
     Ok(())
 }
 
 #[tokio::main]
 async fn main() {
-    let block_number = 18884864u64;
+    let start_block = 18884864u64;
+    let end_block = 18884864u64;
     let rpc_url =
         Url::parse("https://eth-mainnet.g.alchemy.com/v2/hIxcf_hqT9It2hS8iCFeHKklL8tNyXNF")
             .expect("Invalid RPC URL");
-    println!("Fetching block number {} from {}", block_number, rpc_url);
+    println!("Fetching blocks {}..={} from {}", start_block, end_block, rpc_url);
     // Get the input.
-    let sp1_input = get_input(block_number, rpc_url).await.expect("Failed to get input");
+    let sp1_input = get_input(start_block, end_block, rpc_url).await.expect("Failed to get input");
     // Verify the STF.
     verify_stf(sp1_input).expect("Failed to verify STF");
 }
\ No newline at end of file
@@ -0,0 +1,230 @@
+//! An RPC-backed database used for the trusted execution pass.
+//!
+//! Every account, storage slot and ancestor block hash touched during execution
+//! is recorded, so [`RpcDb::get_sp1_input`] can request proofs for exactly that
+//! set and emit a single merged witness for the whole block range.
+
+use std::{
+    collections::BTreeSet,
+    sync::{Arc, Mutex},
+};
+
+use alloy_provider::{Provider as AlloyProvider, ReqwestProvider};
+use alloy_rpc_types::BlockId;
+use async_std::task;
+use reth_primitives::{
+    trie::{AccountProof, Nibbles, StorageProof},
+    Account, Address, Block as RethBlock, Header, B256, U256,
+};
+use reth_provider::ProviderError;
+use revm::{
+    primitives::{AccountInfo, Bytecode},
+    DatabaseRef,
+};
+use revm_primitives::{keccak256, HashMap, KECCAK_EMPTY};
+
+use crate::{FullAccountProof, SP1Input};
+
+/// The state an execution touched, held behind shared handles so the db can be
+/// cloned freely (e.g. into a `CacheDB`) while still accumulating into one set.
+#[derive(Debug, Default)]
+struct Accessed {
+    /// Touched accounts and, per account, the storage slots read from them.
+    accounts: HashMap<Address, BTreeSet<U256>>,
+    /// Ancestor block numbers queried by `BLOCKHASH`, with their hashes.
+    block_hashes: HashMap<u64, B256>,
+}
+
+#[derive(Debug, Clone)]
+pub struct RpcDb {
+    provider: ReqwestProvider,
+    /// The block at whose post-state all reads are served (the range's parent).
+    block: BlockId,
+    /// The pre-state root reads are proven against.
+    state_root: B256,
+    accessed: Arc<Mutex<Accessed>>,
+}
+
+impl RpcDb {
+    pub fn new(provider: ReqwestProvider, block: BlockId, state_root: B256) -> Self {
+        Self { provider, block, state_root, accessed: Arc::new(Mutex::new(Accessed::default())) }
+    }
+
+    /// Fetch an account's info from the RPC, recording the access.
+    pub async fn fetch_account_info(&self, address: Address) -> eyre::Result<AccountInfo> {
+        self.accessed.lock().unwrap().accounts.entry(address).or_default();
+        let proof = self.provider.get_proof(address, Vec::new(), self.block).await?;
+        let code = self.provider.get_code_at(address, self.block).await?;
+        Ok(AccountInfo {
+            balance: proof.balance,
+            nonce: proof.nonce.to::<u64>(),
+            code_hash: proof.code_hash,
+            code: Some(Bytecode::new_raw(code)),
+        })
+    }
+
+    /// Collect proofs for everything touched and assemble the merged witness for
+    /// the block range `blocks`, whose parent is `prev_block`.
+    pub async fn get_sp1_input(&self, prev_block: &RethBlock, blocks: &[RethBlock]) -> SP1Input {
+        // Snapshot the accessed set so we don't hold the lock across awaits.
+        let (accounts, block_hashes) = {
+            let accessed = self.accessed.lock().unwrap();
+            (accessed.accounts.clone(), accessed.block_hashes.clone())
+        };
+
+        let mut address_to_proof = HashMap::new();
+        for (address, slots) in &accounts {
+            let keys: Vec<B256> = slots.iter().map(|slot| B256::from(*slot)).collect();
+            let response = self
+                .provider
+                .get_proof(*address, keys, self.block)
+                .await
+                .expect("failed to fetch account proof");
+            let code = self
+                .provider
+                .get_code_at(*address, self.block)
+                .await
+                .expect("failed to fetch account code");
+            address_to_proof.insert(*address, full_account_proof(response, Bytecode::new_raw(code)));
+        }
+
+        // `eth_getProof` returns only the path to each key, never the sibling
+        // subtrees. When a block deletes an account (or zeroes a storage slot) the
+        // branch it hung off collapses onto its surviving sibling, and rebuilding
+        // the post-state root needs that sibling's node. Recover it from the
+        // range's post-state proof, where the collapse has already happened, and
+        // carry it in the witness. Keys that still exist post-state yield nothing.
+        let post_block: BlockId = blocks.last().map_or(self.block, |block| block.header.number.into());
+        let post_state_root = blocks.last().map_or(self.state_root, |block| block.header.state_root);
+        let mut extra_nodes = Vec::new();
+        for (address, slots) in &accounts {
+            let keys: Vec<B256> = slots.iter().map(|slot| B256::from(*slot)).collect();
+            let response = self
+                .provider
+                .get_proof(*address, keys, post_block)
+                .await
+                .expect("failed to fetch post-state account proof");
+
+            let hashed = crate::mpt::nibbles(keccak256(*address).as_slice());
+            if let Some(sibling) =
+                crate::mpt::recover_sibling(post_state_root, &response.account_proof, &hashed)
+            {
+                extra_nodes.push(sibling);
+            }
+
+            for proof in &response.storage_proof {
+                let slot: B256 = proof.key.0.into();
+                let hashed = crate::mpt::nibbles(keccak256(slot).as_slice());
+                if let Some(sibling) =
+                    crate::mpt::recover_sibling(response.storage_hash, &proof.proof, &hashed)
+                {
+                    extra_nodes.push(sibling);
+                }
+            }
+        }
+
+        // Witness the contiguous ancestor headers the BLOCKHASH chain walk needs:
+        // from the lowest ancestor `BLOCKHASH` reached up to (but excluding) the
+        // range's parent, which is already carried as `prev_block`.
+        let parent_number = prev_block.header.number;
+        let mut ancestor_headers = Vec::new();
+        if let Some(min_number) = block_hashes.keys().copied().filter(|n| *n < parent_number).min() {
+            for number in min_number..parent_number {
+                let alloy_block = self
+                    .provider
+                    .get_block_by_number(number.into(), false)
+                    .await
+                    .expect("failed to fetch ancestor block")
+                    .expect("ancestor block not found");
+                ancestor_headers
+                    .push(Header::try_from(alloy_block.header).expect("invalid ancestor header"));
+            }
+        }
+
+        let block_hashes =
+            block_hashes.into_iter().map(|(number, hash)| (U256::from(number), hash)).collect();
+
+        SP1Input {
+            prev_block: prev_block.clone(),
+            blocks: blocks.to_vec(),
+            address_to_proof,
+            block_hashes,
+            ancestor_headers,
+            extra_nodes,
+        }
+    }
+}
+
+impl DatabaseRef for RpcDb {
+    type Error = ProviderError;
+
+    fn basic_ref(&self, address: Address) -> Result<Option<AccountInfo>, Self::Error> {
+        self.accessed.lock().unwrap().accounts.entry(address).or_default();
+        let info = task::block_on(self.fetch_account_info(address))
+            .map_err(|_| ProviderError::StateForHashNotFound(self.state_root))?;
+        Ok(Some(info))
+    }
+
+    fn code_by_hash_ref(&self, _code_hash: B256) -> Result<Bytecode, Self::Error> {
+        // Code is served inline via `basic_ref`'s `AccountInfo::code`.
+        Ok(Bytecode::new())
+    }
+
+    fn storage_ref(&self, address: Address, index: U256) -> Result<U256, Self::Error> {
+        // Record the slot so `get_sp1_input` fetches a proof for exactly it.
+        self.accessed.lock().unwrap().accounts.entry(address).or_default().insert(index);
+        task::block_on(self.provider.get_storage_at(address, index, self.block))
+            .map_err(|_| ProviderError::StateForHashNotFound(self.state_root))
+    }
+
+    fn block_hash_ref(&self, number: U256) -> Result<B256, Self::Error> {
+        let number = number.to::<u64>();
+        let hash = task::block_on(self.provider.get_block_by_number(number.into(), false))
+            .ok()
+            .flatten()
+            .and_then(|block| block.header.hash)
+            .ok_or(ProviderError::StateForHashNotFound(self.state_root))?;
+
+        // Record the ancestor so its header can be witnessed and the BLOCKHASH
+        // value verified against it later.
+        self.accessed.lock().unwrap().block_hashes.insert(number, hash);
+        Ok(hash)
+    }
+}
+
+/// Build a [`FullAccountProof`] from an `eth_getProof` response and the fetched
+/// bytecode.
+fn full_account_proof(
+    response: alloy_rpc_types::EIP1186AccountProofResponse,
+    code: Bytecode,
+) -> FullAccountProof {
+    let info = Account {
+        nonce: response.nonce.to::<u64>(),
+        balance: response.balance,
+        bytecode_hash: (response.code_hash != KECCAK_EMPTY).then_some(response.code_hash),
+    };
+
+    let mut storage_proofs = HashMap::new();
+    for proof in &response.storage_proof {
+        let slot: B256 = proof.key.0.into();
+        storage_proofs.insert(
+            slot,
+            StorageProof {
+                key: slot,
+                nibbles: Nibbles::unpack(keccak256(slot)),
+                value: proof.value,
+                proof: proof.proof.clone(),
+            },
+        );
+    }
+
+    let account_proof = AccountProof {
+        address: response.address,
+        info: Some(info),
+        proof: response.account_proof.clone(),
+        storage_root: response.storage_hash,
+        storage_proofs: storage_proofs.values().cloned().collect(),
+    };
+
+    FullAccountProof { account_proof, code, storage_proofs }
+}
@@ -0,0 +1,108 @@
+//! The database the STF verifier runs against inside the zkVM.
+//!
+//! Unlike [`crate::provider_db::RpcDb`], `WitnessDb` has no network access: it
+//! serves only state that has been verified against the witness up front. A read
+//! for an account or storage slot that is not in the verified set is therefore a
+//! proof failure, surfaced as an error rather than a silent zero.
+
+use reth_primitives::{Address, B256, U256};
+use reth_provider::ProviderError;
+use revm::{
+    primitives::{AccountInfo, Bytecode},
+    DatabaseRef,
+};
+use revm_primitives::{HashMap, KECCAK_EMPTY};
+
+use crate::SP1Input;
+
+#[derive(Debug, Clone)]
+pub struct WitnessDb {
+    /// Verified account state, keyed by address. A proven-absent account is
+    /// simply missing here and reads back as an empty account.
+    accounts: HashMap<Address, AccountInfo>,
+    /// Verified storage, keyed by address then slot. Only slots proven in the
+    /// witness are present; any other read is a proof failure.
+    storage: HashMap<Address, HashMap<U256, U256>>,
+    /// Verified bytecode, keyed by code hash.
+    bytecode: HashMap<B256, Bytecode>,
+    /// Verified ancestor block hashes, keyed by block number.
+    block_hashes: HashMap<U256, B256>,
+    /// The pre-state root every proof in the witness was checked against.
+    state_root: B256,
+}
+
+impl WitnessDb {
+    /// Build the database from an [`SP1Input`], verifying every account and
+    /// storage proof against the pre-state root before trusting any of it.
+    pub fn new(input: SP1Input) -> Self {
+        let state_root = input.prev_block.header.state_root;
+        let mut accounts = HashMap::new();
+        let mut storage = HashMap::new();
+        let mut bytecode = HashMap::new();
+
+        for (address, proof) in &input.address_to_proof {
+            proof.verify(state_root).expect("invalid account proof in witness");
+
+            if let Some(account) = proof.account_proof.info {
+                let code_hash = account.bytecode_hash.unwrap_or(KECCAK_EMPTY);
+                accounts.insert(
+                    *address,
+                    AccountInfo {
+                        balance: account.balance,
+                        nonce: account.nonce,
+                        code_hash,
+                        code: Some(proof.code.clone()),
+                    },
+                );
+                bytecode.insert(code_hash, proof.code.clone());
+            }
+
+            // Record exactly the slots the witness proved for this account.
+            let mut slots = HashMap::new();
+            for (slot, storage_proof) in &proof.storage_proofs {
+                slots.insert(U256::from_be_bytes(slot.0), storage_proof.value);
+            }
+            storage.insert(*address, slots);
+        }
+
+        Self { accounts, storage, bytecode, block_hashes: input.block_hashes, state_root }
+    }
+}
+
+impl DatabaseRef for WitnessDb {
+    type Error = ProviderError;
+
+    fn basic_ref(&self, address: Address) -> Result<Option<AccountInfo>, Self::Error> {
+        // Every witnessed address has a (possibly empty) entry in `storage`; an
+        // address without one was never proven, so fail instead of reporting it empty.
+        if !self.storage.contains_key(&address) {
+            return Err(ProviderError::StateForHashNotFound(self.state_root))
+        }
+        // A proven-absent account is correctly reported as empty (`None`).
+        Ok(self.accounts.get(&address).cloned())
+    }
+
+    fn code_by_hash_ref(&self, code_hash: B256) -> Result<Bytecode, Self::Error> {
+        self.bytecode
+            .get(&code_hash)
+            .cloned()
+            .ok_or(ProviderError::StateForHashNotFound(self.state_root))
+    }
+
+    fn storage_ref(&self, address: Address, index: U256) -> Result<U256, Self::Error> {
+        // A witnessed account with no entry for this slot means the slot was
+        // never proven: fail loudly instead of returning zero and trusting it.
+        self.storage
+            .get(&address)
+            .and_then(|slots| slots.get(&index))
+            .copied()
+            .ok_or(ProviderError::StateForHashNotFound(self.state_root))
+    }
+
+    fn block_hash_ref(&self, number: U256) -> Result<B256, Self::Error> {
+        self.block_hashes
+            .get(&number)
+            .copied()
+            .ok_or(ProviderError::StateForHashNotFound(self.state_root))
+    }
+}